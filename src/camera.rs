@@ -1,25 +1,31 @@
-use crate::vec_util::{normalize, V3d};
+use crate::vec_util::{cross, normalize, random_in_unit_disk, V3d};
 
 pub struct Camera {
-    pub center: V3d,
-    pub viewport_width: f64,
-    pub viewport_height: f64,
-    pub focal_length: f64,
+    pub look_from: V3d,
+    pub look_at: V3d,
+    pub vup: V3d,
+    pub vfov: f64,
+    pub aspect_ratio: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
 }
 
 impl Camera {
-    pub fn ray_generator(&self, image_width: u32, image_height: u32) -> RayGenerator {
-        RayGenerator::new(self, image_width, image_height)
+    pub fn ray_generator(&self) -> RayGenerator {
+        RayGenerator::new(self)
     }
 }
 
 impl Default for Camera {
     fn default() -> Self {
         Camera {
-            center: V3d::new(0., 0., 0.),
-            viewport_height: 2.0,
-            viewport_width: 2.0 * 16. / 9., // todo store aspect ratio in one place (cam + raymarcher)
-            focal_length: 1.,
+            look_from: V3d::new(0., 0., 0.),
+            look_at: V3d::new(0., 0., -1.),
+            vup: V3d::new(0., 1., 0.),
+            vfov: 90.0,
+            aspect_ratio: 16. / 9.,
+            aperture: 0.0,
+            focus_dist: 1.0,
         }
     }
 }
@@ -29,52 +35,48 @@ pub struct RayGenerator {
     horizontal: V3d,
     vertical: V3d,
     lower_left_corner: V3d,
-    image_width: u32,
-    image_height: u32,
-    x: u32,
-    y: u32,
+    u: V3d,
+    v: V3d,
+    lens_radius: f64,
 }
 
 impl RayGenerator {
-    fn new(cam: &Camera, image_width: u32, image_height: u32) -> Self {
-        let horizontal = V3d::new(cam.viewport_width, 0., 0.);
-        let vertical = V3d::new(0., cam.viewport_height, 0.);
+    fn new(cam: &Camera) -> Self {
+        let theta = cam.vfov.to_radians();
+        let viewport_height = 2.0 * f64::tan(theta / 2.0);
+        let viewport_width = cam.aspect_ratio * viewport_height;
+
+        let w = normalize(cam.look_from - cam.look_at);
+        let u = normalize(cross(cam.vup, w));
+        let v = cross(w, u);
+
+        let horizontal = u * (cam.focus_dist * viewport_width);
+        let vertical = v * (cam.focus_dist * viewport_height);
 
         RayGenerator {
-            origin: cam.center,
+            origin: cam.look_from,
             horizontal,
             vertical,
-            lower_left_corner: cam.center
-                - V3d::new(0., 0., cam.focal_length)
+            lower_left_corner: cam.look_from
                 - horizontal / 2.
-                - vertical / 2.,
-            image_width,
-            image_height,
-            x: 0,
-            y: image_height - 1,
+                - vertical / 2.
+                - w * cam.focus_dist,
+            u,
+            v,
+            lens_radius: cam.aperture / 2.0,
         }
     }
-}
-
-impl Iterator for RayGenerator {
-    type Item = V3d;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.y == 0 && self.x >= self.image_width {
-            return None;
-        }
-
-        let v = self.y as f64 / (self.image_height - 1) as f64;
-        let u = self.x as f64 / (self.image_width - 1) as f64;
-        let screen_v = self.lower_left_corner + self.horizontal * u + self.vertical * v;
-        let direction = normalize(screen_v - self.origin);
-
-        self.x += 1;
-        if self.x >= self.image_width && self.y > 0 {
-            self.x = 0;
-            self.y -= 1;
-        }
+    pub fn ray_at(&self, s: f64, t: f64) -> (V3d, V3d) {
+        let rd = random_in_unit_disk() * self.lens_radius;
+        let offset = self.u * rd.x + self.v * rd.y;
+        let origin = self.origin + offset;
+        let direction = normalize(
+            self.lower_left_corner + self.horizontal * s + self.vertical * t
+                - self.origin
+                - offset,
+        );
 
-        Some(direction)
+        (origin, direction)
     }
 }
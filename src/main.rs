@@ -1,20 +1,35 @@
 mod camera;
+mod output;
 mod scene;
 mod vec_util;
 
 use crate::camera::Camera;
-use crate::scene::{Color, ExclusionObject, Scene, Sierpinski, Sphere};
+use crate::output::{output_for_extension, Image};
+use crate::scene::{Color, Light, Material, Plane, Scene, Sierpinski, Sphere};
+use indicatif::ProgressBar;
 use show_image::{create_window, event, ImageInfo, ImageView};
-use std::fs::OpenOptions;
-use std::io;
-use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::available_parallelism;
 
-use crate::vec_util::{length, normalize, V3d};
+use crate::vec_util::{cosine_sample_hemisphere, length, mul_elementwise, normalize, V3d};
 
-struct Image {
-    pixels: Vec<Color>,
-    image_width: u32,
-    image_height: u32,
+const TILE_SIZE: u32 = 16;
+
+/// Raw pointer into a pixel buffer, shared read-write across render threads.
+/// Sound because every tile covers a disjoint set of indices, so no two
+/// threads ever write the same slot.
+struct TileBuffer {
+    ptr: *mut Color,
+}
+
+unsafe impl Send for TileBuffer {}
+unsafe impl Sync for TileBuffer {}
+
+impl TileBuffer {
+    unsafe fn write(&self, index: usize, color: Color) {
+        *self.ptr.add(index) = color;
+    }
 }
 
 struct RayMarcher {
@@ -23,6 +38,11 @@ struct RayMarcher {
     max_steps: u32,
     aspect_ratio: f64,
     image_width: u32,
+    samples_per_pixel: u32,
+    shadow_softness: f64,
+    shadow_max_steps: u32,
+    threads: usize,
+    max_bounces: u32,
 }
 
 impl Default for RayMarcher {
@@ -33,39 +53,90 @@ impl Default for RayMarcher {
             max_steps: 100,
             image_width: 400,
             aspect_ratio: 16. / 9.,
+            samples_per_pixel: 10,
+            shadow_softness: 16.0,
+            shadow_max_steps: 64,
+            threads: available_parallelism().map(|n| n.get()).unwrap_or(1),
+            max_bounces: 8,
         }
     }
 }
 
 impl RayMarcher {
     fn march(&self, scene: &Scene, cam: &Camera) -> Image {
+        self.render_tiled(cam, |origin, direction| self.send_ray(origin, direction, scene))
+    }
+
+    fn pathmarch(&self, scene: &Scene, cam: &Camera) -> Image {
+        self.render_tiled(cam, |origin, direction| self.trace_path(origin, direction, scene))
+    }
+
+    fn render_tiled<F>(&self, cam: &Camera, sample_color: F) -> Image
+    where
+        F: Fn(V3d, V3d) -> Color + Sync,
+    {
         let image_height = (self.image_width as f64 / self.aspect_ratio) as u32;
-        let distances: Vec<f64> = cam
-            .ray_generator(self.image_width, image_height)
-            .map(|dir| self.send_ray_dist(cam.center, dir, scene))
-            .collect();
-
-        let max_dist = distances
-            .iter()
-            .max_by(|&a, &b| a.partial_cmp(b).expect("NaN values should not happen"))
-            .expect("Empty pixels?");
-
-        let pixels = distances
-            .iter()
-            .map(|&d| {
-                if d < 0.0 {
-                    (0, 0, 0)
-                } else {
-                    let g = (1.0 - d / max_dist) * 255.0;
-                    (g as u8, g as u8, g as u8)
-                }
-            })
-            .collect();
+        let ray_generator = cam.ray_generator();
+
+        let tiles_x = (self.image_width + TILE_SIZE - 1) / TILE_SIZE;
+        let tiles_y = (image_height + TILE_SIZE - 1) / TILE_SIZE;
+        let tile_count = (tiles_x * tiles_y) as usize;
+
+        let mut pixels = vec![(0u8, 0u8, 0u8); (self.image_width * image_height) as usize];
+        let buffer = TileBuffer {
+            ptr: pixels.as_mut_ptr(),
+        };
+
+        let next_tile = AtomicUsize::new(0);
+        let progress = ProgressBar::new(tile_count as u64);
+
+        crossbeam::scope(|s| {
+            for _ in 0..self.threads {
+                s.spawn(|_| loop {
+                    let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if tile >= tile_count {
+                        break;
+                    }
+
+                    let tile_x = (tile as u32 % tiles_x) * TILE_SIZE;
+                    let tile_y = (tile as u32 / tiles_x) * TILE_SIZE;
+                    let tile_w = TILE_SIZE.min(self.image_width - tile_x);
+                    let tile_h = TILE_SIZE.min(image_height - tile_y);
+
+                    for local_y in 0..tile_h {
+                        // `y` counts up from the bottom of the viewport, but
+                        // pixels are stored top row first.
+                        let y = tile_y + local_y;
+                        let row = image_height - 1 - y;
+                        for local_x in 0..tile_w {
+                            let x = tile_x + local_x;
+                            let mut accum = (0.0, 0.0, 0.0);
+                            for _sample in 0..self.samples_per_pixel {
+                                let u = (x as f64 + rand::random::<f64>())
+                                    / (self.image_width - 1) as f64;
+                                let v = (y as f64 + rand::random::<f64>())
+                                    / (image_height - 1) as f64;
+                                let (origin, direction) = ray_generator.ray_at(u, v);
+                                let color = sample_color(origin, direction);
+                                accum.0 += color.0 as f64;
+                                accum.1 += color.1 as f64;
+                                accum.2 += color.2 as f64;
+                            }
+
+                            let pixel =
+                                Self::average_and_gamma_correct(accum, self.samples_per_pixel);
+                            let index = row as usize * self.image_width as usize + x as usize;
+                            unsafe { buffer.write(index, pixel) };
+                        }
+                    }
+
+                    progress.inc(1);
+                });
+            }
+        })
+        .expect("a render thread panicked");
 
-        // let pixels = cam
-        //     .ray_generator(self.image_width, image_height)
-        //     .map(|dir| self.send_ray(cam.center, dir, scene))
-        //     .collect();
+        progress.finish();
 
         Image {
             pixels,
@@ -74,12 +145,22 @@ impl RayMarcher {
         }
     }
 
+    fn average_and_gamma_correct(sum: (f64, f64, f64), samples: u32) -> Color {
+        let gamma = |channel_sum: f64| {
+            let linear = (channel_sum / samples as f64 / 255.0).clamp(0.0, 1.0);
+            (f64::sqrt(linear) * 255.0) as u8
+        };
+
+        (gamma(sum.0), gamma(sum.1), gamma(sum.2))
+    }
+
     fn send_ray<'a>(&'a self, origin: V3d, direction: V3d, scene: &'a Scene) -> Color {
         let mut depth = 0.0;
         for _step in 0..self.max_steps {
-            let (dist, color) = scene.sdf_with_color(origin + direction * depth);
+            let p = origin + direction * depth;
+            let (dist, material) = scene.sdf_with_material(p);
             if dist < self.ray_dist_epsilon {
-                return color;
+                return self.apply_lighting(p, direction, &material, scene);
             }
 
             depth += dist
@@ -88,34 +169,65 @@ impl RayMarcher {
         scene.background_color
     }
 
-    fn send_ray_dist<'a>(&'a self, origin: V3d, direction: V3d, scene: &'a Scene) -> f64 {
-        let mut depth = 0.0;
-        for _step in 0..self.max_steps {
-            let (dist, _color) = scene.sdf_with_color(origin + direction * depth);
-            if dist < self.ray_dist_epsilon {
-                return depth;
+    fn trace_path(&self, origin: V3d, direction: V3d, scene: &Scene) -> Color {
+        let mut ray_origin = origin;
+        let mut ray_direction = direction;
+        let mut throughput = V3d::new(1.0, 1.0, 1.0);
+        let mut radiance = V3d::new(0.0, 0.0, 0.0);
+
+        for bounce in 0..self.max_bounces {
+            let mut depth = 0.0;
+            let mut hit = None;
+            for _step in 0..self.max_steps {
+                let p = ray_origin + ray_direction * depth;
+                let (dist, material) = scene.sdf_with_material(p);
+                if dist < self.ray_dist_epsilon {
+                    hit = Some((p, material));
+                    break;
+                }
+
+                depth += dist;
             }
 
-            depth += dist
+            let (p, material) = match hit {
+                Some(hit) => hit,
+                None => {
+                    let background = V3d::new(
+                        scene.background_color.0 as f64 / 255.0,
+                        scene.background_color.1 as f64 / 255.0,
+                        scene.background_color.2 as f64 / 255.0,
+                    );
+                    radiance = radiance + mul_elementwise(throughput, background);
+                    break;
+                }
+            };
+
+            radiance = radiance + mul_elementwise(throughput, material.emission);
+
+            if bounce >= 3 {
+                let survive_prob = material
+                    .diffuse
+                    .x
+                    .max(material.diffuse.y)
+                    .max(material.diffuse.z)
+                    .clamp(0.05, 0.95);
+                if rand::random::<f64>() > survive_prob {
+                    break;
+                }
+                throughput = throughput / survive_prob;
+            }
+
+            let normal = self.get_normal(p, scene);
+            let bounce_direction = cosine_sample_hemisphere(normal);
+            ray_origin = p + normal * (self.ray_dist_epsilon * 2.0);
+            ray_direction = bounce_direction;
+            // The cosine term and the cosine-weighted pdf cancel, leaving
+            // just the surface albedo.
+            throughput = mul_elementwise(throughput, material.diffuse);
         }
 
-        -1.0
-
-        // while min_dist > self.ray_dist_epsilon && steps < self.max_steps {
-        //     let (dist, color) = scene.sdf_with_color(current_point);
-        //
-        //     min_dist = dist;
-        //     last_color = color;
-        //     current_point = current_point + direction * min_dist;
-        //     steps += 1;
-        // }
-        //
-        // if steps >= self.max_steps {
-        //     -1.0
-        // } else {
-        //     // self.apply_lighting(direction, last_color, scene)
-        //     length(current_point - origin)
-        // }
+        let to_byte = |c: f64| (c * 255.0).clamp(0.0, 255.0) as u8;
+        (to_byte(radiance.x), to_byte(radiance.y), to_byte(radiance.z))
     }
 
     fn get_normal(&self, p: V3d, scene: &Scene) -> V3d {
@@ -131,46 +243,52 @@ impl RayMarcher {
         normalize(v)
     }
 
-    fn apply_lighting(&self, p: V3d, color: Color, scene: &Scene) -> Color {
-        let ambient = V3d::new(0.5, 0.5, 0.5);
+    fn shadow_factor(&self, p: V3d, light_dir: V3d, light_dist: f64, scene: &Scene) -> f64 {
+        let mut res = 1.0;
+        let mut t = self.ray_dist_epsilon * 16.0;
+        for _step in 0..self.shadow_max_steps {
+            if t >= light_dist {
+                break;
+            }
 
-        let normal = self.get_normal(p, scene);
-        let light_color = V3d::new(1.0, 1.0, 1.0);
-        let light_source = V3d::new(0., 1.5, -4.0);
-        let diffuse_strength = f64::max(0.0, light_source.dot(normal));
-        let diffuse = light_color * diffuse_strength;
-
-        let lighting = ambient * 0.0 + diffuse;
-
-        (
-            (color.0 as f64 * lighting.x) as u8,
-            (color.1 as f64 * lighting.y) as u8,
-            (color.2 as f64 * lighting.z) as u8,
-        )
-    }
-}
+            let h = scene.sdf(p + light_dir * t);
+            if h < self.ray_dist_epsilon {
+                return 0.0;
+            }
 
-fn save_as_ppm(img: &Image) -> io::Result<()> {
-    let file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open("./renders/image.ppm")?;
-    let mut writer = BufWriter::new(file);
-
-    writer.write_all("P3\n".as_bytes())?;
-    writer.write_all(format!("{} {}\n", img.image_width, img.image_height).as_bytes())?;
-    writer.write_all("255".as_bytes())?;
-
-    for (i, pixel) in img.pixels.iter().enumerate() {
-        if i % (img.image_height as usize) == 0 {
-            writer.write_all("\n".as_bytes())?;
+            res = f64::min(res, self.shadow_softness * h / t);
+            t += h;
         }
 
-        writer.write_all(format!("{} {} {} ", pixel.0, pixel.1, pixel.2).as_bytes())?
+        res
     }
 
-    writer.flush()
+    fn apply_lighting(&self, p: V3d, ray_direction: V3d, material: &Material, scene: &Scene) -> Color {
+        let normal = self.get_normal(p, scene);
+        let view = normalize(ray_direction * -1.0);
+
+        let mut lighting = mul_elementwise(scene.ambient, material.ambient);
+
+        for light in &scene.lights {
+            let to_light = light.position - p;
+            let light_dist = length(to_light);
+            let light_dir = to_light / light_dist;
+            let shadow = self.shadow_factor(p, light_dir, light_dist, scene);
+
+            let diffuse_strength = f64::max(0.0, normal.dot(light_dir));
+            let diffuse = mul_elementwise(light.intensity, material.diffuse) * diffuse_strength;
+
+            let reflected = normal * (2.0 * normal.dot(light_dir)) - light_dir;
+            let specular_strength =
+                f64::max(0.0, reflected.dot(view)).powf(material.shininess);
+            let specular = mul_elementwise(light.intensity, material.specular) * specular_strength;
+
+            lighting = lighting + (diffuse + specular) * shadow;
+        }
+
+        let to_byte = |c: f64| (c * 255.0).clamp(0.0, 255.0) as u8;
+        (to_byte(lighting.x), to_byte(lighting.y), to_byte(lighting.z))
+    }
 }
 
 fn create_test_image(image_width: u32, image_height: u32) -> Vec<Color> {
@@ -193,58 +311,118 @@ fn create_test_image(image_width: u32, image_height: u32) -> Vec<Color> {
     image
 }
 
-fn convert_pixels(pixels: &Vec<Color>) -> Vec<u8> {
-    let mut result = vec![];
-    for x in pixels {
-        result.push(x.0);
-        result.push(x.1);
-        result.push(x.2);
-    }
-
-    result
-}
-
 fn render_default_scene() -> Image {
     let mut scene = Scene::default();
-    // let a = (Box::new(Sphere {
-    //     center: V3d::new(0.0, 0., -5.),
-    //     color: (200, 0, 0),
-    // }));
-    //
-    // let b = (Box::new(Sphere {
-    //     center: V3d::new(0., 1.5, -4.0),
-    //     color: (200, 0, 0),
-    // }));
-    //
-    // scene.objects.push(Box::new(ExclusionObject { a, b }));
-
     scene.objects.push(Box::new(Sierpinski {
         // center: V3d::new(0., 0., -5.),
         color: (200, 0, 0),
     }));
 
+    scene.lights.push(Light {
+        position: V3d::new(0., 1.5, -4.0),
+        intensity: V3d::new(1.0, 1.0, 1.0),
+    });
+
     let raymarcher = RayMarcher::default();
+    let look_from = V3d::new(0., 0., 3.0);
+    let look_at = V3d::new(0., 0., 0.);
     let cam = Camera {
-        center: V3d::new(0., 0., 3.0),
-        viewport_height: 2.0,
-        viewport_width: 2.0 * 16. / 9., // todo store aspect ratio in one place (cam + raymarcher)
-        focal_length: 1.,
+        look_from,
+        look_at,
+        vup: V3d::new(0., 1., 0.),
+        vfov: 90.0,
+        aspect_ratio: raymarcher.aspect_ratio,
+        aperture: 0.0,
+        focus_dist: length(look_from - look_at),
     };
 
     raymarcher.march(&scene, &cam)
 }
 
+fn render_cornell_scene() -> Image {
+    let mut scene = Scene::default();
+    let wall_color = (200, 200, 200);
+
+    // Floor, ceiling, back, left (red) and right (green) walls form an open
+    // box; an emissive plane just below the ceiling is the only light source.
+    scene.objects.push(Box::new(Plane {
+        point: V3d::new(0., -3., 0.),
+        normal: V3d::new(0., 1., 0.),
+        color: wall_color,
+        emission: V3d::new(0., 0., 0.),
+    }));
+    scene.objects.push(Box::new(Plane {
+        point: V3d::new(0., 3., 0.),
+        normal: V3d::new(0., -1., 0.),
+        color: wall_color,
+        emission: V3d::new(0., 0., 0.),
+    }));
+    scene.objects.push(Box::new(Plane {
+        point: V3d::new(0., 0., -6.),
+        normal: V3d::new(0., 0., 1.),
+        color: wall_color,
+        emission: V3d::new(0., 0., 0.),
+    }));
+    scene.objects.push(Box::new(Plane {
+        point: V3d::new(-3., 0., 0.),
+        normal: V3d::new(1., 0., 0.),
+        color: (200, 60, 60),
+        emission: V3d::new(0., 0., 0.),
+    }));
+    scene.objects.push(Box::new(Plane {
+        point: V3d::new(3., 0., 0.),
+        normal: V3d::new(-1., 0., 0.),
+        color: (60, 200, 60),
+        emission: V3d::new(0., 0., 0.),
+    }));
+    scene.objects.push(Box::new(Plane {
+        point: V3d::new(0., 2.9, 0.),
+        normal: V3d::new(0., -1., 0.),
+        color: (255, 255, 255),
+        emission: V3d::new(8.0, 8.0, 8.0),
+    }));
+
+    scene.objects.push(Box::new(Sphere {
+        center: V3d::new(0., -1., -4.),
+        color: (220, 220, 220),
+    }));
+
+    let raymarcher = RayMarcher::default();
+    let look_from = V3d::new(0., 0., 2.0);
+    let look_at = V3d::new(0., 0., -4.0);
+    let cam = Camera {
+        look_from,
+        look_at,
+        vup: V3d::new(0., 1., 0.),
+        vfov: 60.0,
+        aspect_ratio: raymarcher.aspect_ratio,
+        aperture: 0.0,
+        focus_dist: length(look_from - look_at),
+    };
+
+    raymarcher.pathmarch(&scene, &cam)
+}
+
 #[show_image::main]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Rendering image ...");
     // let img = create_test_image(500, 900);
-    let img = render_default_scene();
+    let path_trace = std::env::args().any(|arg| arg == "--pathtrace");
+    let img = if path_trace {
+        render_cornell_scene()
+    } else {
+        render_default_scene()
+    };
 
     println!("Saving to file ...");
-    save_as_ppm(&img).expect("Could not save image");
+    let output_path = Path::new("./renders/image.png");
+    let writer = output_for_extension(output_path.extension().and_then(|ext| ext.to_str()));
+    writer
+        .write(&img, output_path)
+        .expect("Could not save image");
 
     println!("Opening image in window ...");
-    let pixels_u8 = convert_pixels(&img.pixels);
+    let pixels_u8 = img.to_rgb_bytes();
     let v_image = ImageView::new(
         ImageInfo::rgb8(img.image_width, img.image_height),
         &pixels_u8,
@@ -0,0 +1,86 @@
+use crate::scene::Color;
+use image::{ImageBuffer, RgbImage};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub struct Image {
+    pub pixels: Vec<Color>,
+    pub image_width: u32,
+    pub image_height: u32,
+}
+
+impl Image {
+    pub fn to_rgb_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in &self.pixels {
+            bytes.push(pixel.0);
+            bytes.push(pixel.1);
+            bytes.push(pixel.2);
+        }
+
+        bytes
+    }
+}
+
+pub trait Output {
+    fn write(&self, image: &Image, path: &Path) -> io::Result<()>;
+}
+
+pub struct PpmAsciiOutput;
+
+impl Output for PpmAsciiOutput {
+    fn write(&self, image: &Image, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(
+            format!("P3\n{} {}\n255\n", image.image_width, image.image_height).as_bytes(),
+        )?;
+
+        for row in image.pixels.chunks(image.image_width as usize) {
+            for pixel in row {
+                writer.write_all(format!("{} {} {} ", pixel.0, pixel.1, pixel.2).as_bytes())?;
+            }
+            writer.write_all(b"\n")?;
+        }
+
+        writer.flush()
+    }
+}
+
+pub struct PpmBinaryOutput;
+
+impl Output for PpmBinaryOutput {
+    fn write(&self, image: &Image, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(
+            format!("P6\n{} {}\n255\n", image.image_width, image.image_height).as_bytes(),
+        )?;
+        writer.write_all(&image.to_rgb_bytes())?;
+
+        writer.flush()
+    }
+}
+
+pub struct PngOutput;
+
+impl Output for PngOutput {
+    fn write(&self, image: &Image, path: &Path) -> io::Result<()> {
+        let buffer: RgbImage =
+            ImageBuffer::from_raw(image.image_width, image.image_height, image.to_rgb_bytes())
+                .expect("pixel buffer length should match image dimensions");
+
+        buffer
+            .save(path)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+}
+
+pub fn output_for_extension(extension: Option<&str>) -> Box<dyn Output> {
+    match extension.map(str::to_lowercase).as_deref() {
+        Some("png") => Box::new(PngOutput),
+        _ => Box::new(PpmBinaryOutput),
+    }
+}
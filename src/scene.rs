@@ -2,9 +2,46 @@ use crate::vec_util::{length, V3d};
 
 pub type Color = (u8, u8, u8);
 
-pub trait SceneObject {
+pub struct Material {
+    pub ambient: V3d,
+    pub diffuse: V3d,
+    pub specular: V3d,
+    pub shininess: f64,
+    pub emission: V3d,
+}
+
+impl Material {
+    pub fn from_color(color: Color) -> Self {
+        let diffuse = V3d::new(
+            color.0 as f64 / 255.0,
+            color.1 as f64 / 255.0,
+            color.2 as f64 / 255.0,
+        );
+
+        Material {
+            ambient: diffuse * 0.1,
+            diffuse,
+            specular: V3d::new(0.5, 0.5, 0.5),
+            shininess: 32.0,
+            emission: V3d::new(0., 0., 0.),
+        }
+    }
+}
+
+pub struct Light {
+    pub position: V3d,
+    pub intensity: V3d,
+}
+
+pub trait SceneObject: Send + Sync {
     fn get_sdf(&self, p: V3d) -> f64;
-    fn get_color(&self) -> Color;
+    fn get_material(&self, p: V3d) -> Material;
+
+    /// Default recomputes via get_sdf/get_material; combinators override to
+    /// avoid recomputing their children's distances twice.
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        (self.get_sdf(p), self.get_material(p))
+    }
 }
 
 pub struct Sphere {
@@ -17,23 +54,190 @@ impl SceneObject for Sphere {
         f64::sqrt((self.center - p).norm2()) - 2.
     }
 
-    fn get_color(&self) -> Color {
-        self.color
+    fn get_material(&self, _p: V3d) -> Material {
+        Material::from_color(self.color)
     }
 }
 
-pub struct ExclusionObject {
+/// `normal` is expected to be unit length.
+pub struct Plane {
+    pub point: V3d,
+    pub normal: V3d,
+    pub color: Color,
+    pub emission: V3d,
+}
+
+impl SceneObject for Plane {
+    fn get_sdf(&self, p: V3d) -> f64 {
+        (p - self.point).dot(self.normal)
+    }
+
+    fn get_material(&self, _p: V3d) -> Material {
+        let mut material = Material::from_color(self.color);
+        material.emission = self.emission;
+        material
+    }
+}
+
+/// Returns `(smin(a, b, k), h)`; `h` is `1` when `a` dominates, `0` when `b` does.
+fn smin(a: f64, b: f64, k: f64) -> (f64, f64) {
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    let value = b * (1.0 - h) + a * h - k * h * (1.0 - h);
+    (value, h)
+}
+
+fn blend_material(a: Material, b: Material, h: f64) -> Material {
+    let mix = |x: V3d, y: V3d| x * (1.0 - h) + y * h;
+    Material {
+        ambient: mix(b.ambient, a.ambient),
+        diffuse: mix(b.diffuse, a.diffuse),
+        specular: mix(b.specular, a.specular),
+        shininess: b.shininess * (1.0 - h) + a.shininess * h,
+        emission: mix(b.emission, a.emission),
+    }
+}
+
+pub struct Union {
     pub a: Box<dyn SceneObject>,
     pub b: Box<dyn SceneObject>,
 }
 
-impl SceneObject for ExclusionObject {
+impl SceneObject for Union {
+    fn get_sdf(&self, p: V3d) -> f64 {
+        f64::min(self.a.get_sdf(p), self.b.get_sdf(p))
+    }
+
+    fn get_material(&self, p: V3d) -> Material {
+        self.get_sdf_and_material(p).1
+    }
+
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        let (dist_a, material_a) = self.a.get_sdf_and_material(p);
+        let (dist_b, material_b) = self.b.get_sdf_and_material(p);
+        if dist_a <= dist_b {
+            (dist_a, material_a)
+        } else {
+            (dist_b, material_b)
+        }
+    }
+}
+
+pub struct Intersection {
+    pub a: Box<dyn SceneObject>,
+    pub b: Box<dyn SceneObject>,
+}
+
+impl SceneObject for Intersection {
+    fn get_sdf(&self, p: V3d) -> f64 {
+        f64::max(self.a.get_sdf(p), self.b.get_sdf(p))
+    }
+
+    fn get_material(&self, p: V3d) -> Material {
+        self.get_sdf_and_material(p).1
+    }
+
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        let (dist_a, material_a) = self.a.get_sdf_and_material(p);
+        let (dist_b, material_b) = self.b.get_sdf_and_material(p);
+        if dist_a >= dist_b {
+            (dist_a, material_a)
+        } else {
+            (dist_b, material_b)
+        }
+    }
+}
+
+pub struct Subtraction {
+    pub a: Box<dyn SceneObject>,
+    pub b: Box<dyn SceneObject>,
+}
+
+impl SceneObject for Subtraction {
     fn get_sdf(&self, p: V3d) -> f64 {
         f64::max(self.a.get_sdf(p), -self.b.get_sdf(p))
     }
 
-    fn get_color(&self) -> Color {
-        (100, 100, 100)
+    fn get_material(&self, p: V3d) -> Material {
+        self.get_sdf_and_material(p).1
+    }
+
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        let (dist_a, material_a) = self.a.get_sdf_and_material(p);
+        let (dist_b, material_b) = self.b.get_sdf_and_material(p);
+        if dist_a >= -dist_b {
+            (dist_a, material_a)
+        } else {
+            (-dist_b, material_b)
+        }
+    }
+}
+
+pub struct SmoothUnion {
+    pub a: Box<dyn SceneObject>,
+    pub b: Box<dyn SceneObject>,
+    pub k: f64,
+}
+
+impl SceneObject for SmoothUnion {
+    fn get_sdf(&self, p: V3d) -> f64 {
+        smin(self.a.get_sdf(p), self.b.get_sdf(p), self.k).0
+    }
+
+    fn get_material(&self, p: V3d) -> Material {
+        self.get_sdf_and_material(p).1
+    }
+
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        let (dist_a, material_a) = self.a.get_sdf_and_material(p);
+        let (dist_b, material_b) = self.b.get_sdf_and_material(p);
+        let (dist, h) = smin(dist_a, dist_b, self.k);
+        (dist, blend_material(material_a, material_b, h))
+    }
+}
+
+pub struct SmoothIntersection {
+    pub a: Box<dyn SceneObject>,
+    pub b: Box<dyn SceneObject>,
+    pub k: f64,
+}
+
+impl SceneObject for SmoothIntersection {
+    fn get_sdf(&self, p: V3d) -> f64 {
+        -smin(-self.a.get_sdf(p), -self.b.get_sdf(p), self.k).0
+    }
+
+    fn get_material(&self, p: V3d) -> Material {
+        self.get_sdf_and_material(p).1
+    }
+
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        let (dist_a, material_a) = self.a.get_sdf_and_material(p);
+        let (dist_b, material_b) = self.b.get_sdf_and_material(p);
+        let (smin_val, h) = smin(-dist_a, -dist_b, self.k);
+        (-smin_val, blend_material(material_a, material_b, h))
+    }
+}
+
+pub struct SmoothSubtraction {
+    pub a: Box<dyn SceneObject>,
+    pub b: Box<dyn SceneObject>,
+    pub k: f64,
+}
+
+impl SceneObject for SmoothSubtraction {
+    fn get_sdf(&self, p: V3d) -> f64 {
+        -smin(-self.a.get_sdf(p), self.b.get_sdf(p), self.k).0
+    }
+
+    fn get_material(&self, p: V3d) -> Material {
+        self.get_sdf_and_material(p).1
+    }
+
+    fn get_sdf_and_material(&self, p: V3d) -> (f64, Material) {
+        let (dist_a, material_a) = self.a.get_sdf_and_material(p);
+        let (dist_b, material_b) = self.b.get_sdf_and_material(p);
+        let (smin_val, h) = smin(-dist_a, dist_b, self.k);
+        (-smin_val, blend_material(material_a, material_b, h))
     }
 }
 
@@ -79,15 +283,27 @@ impl SceneObject for Sierpinski {
         length(z) * f64::powf(SCALE, -n as f64)
     }
 
-    fn get_color(&self) -> Color {
-        self.color
+    fn get_material(&self, _p: V3d) -> Material {
+        Material::from_color(self.color)
     }
 }
 
-#[derive(Default)]
 pub struct Scene {
     pub objects: Vec<Box<dyn SceneObject>>,
     pub background_color: Color,
+    pub lights: Vec<Light>,
+    pub ambient: V3d,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene {
+            objects: Vec::new(),
+            background_color: (0, 0, 0),
+            lights: Vec::new(),
+            ambient: V3d::new(0.1, 0.1, 0.1),
+        }
+    }
 }
 
 impl Scene {
@@ -99,11 +315,54 @@ impl Scene {
             .expect("Scene should not be empty")
     }
 
-    pub fn sdf_with_color(&self, p: V3d) -> (f64, Color) {
+    pub fn sdf_with_material(&self, p: V3d) -> (f64, Material) {
         self.objects
             .iter()
-            .map(|obj| (obj.get_sdf(p), obj.get_color()))
+            .map(|obj| obj.get_sdf_and_material(p))
             .min_by(|(a, _), (b, _)| a.partial_cmp(b).expect("NaN values should not happen"))
             .expect("Scene should not be empty")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smin_favors_the_smaller_value_far_from_the_blend_radius() {
+        let (value, h) = smin(-1.0, 5.0, 0.1);
+        assert!((value - -1.0).abs() < 1e-6);
+        assert!((h - 1.0).abs() < 1e-6);
+
+        let (value, h) = smin(5.0, -1.0, 0.1);
+        assert!((value - -1.0).abs() < 1e-6);
+        assert!(h.abs() < 1e-6);
+    }
+
+    #[test]
+    fn smin_is_symmetric_and_smaller_than_either_input_at_the_midpoint() {
+        let (value, h) = smin(1.0, 1.0, 0.5);
+        assert!((h - 0.5).abs() < 1e-6);
+        assert!(value < 1.0);
+    }
+
+    #[test]
+    fn blend_material_interpolates_toward_the_dominant_side() {
+        let red = V3d::new(1.0, 0.0, 0.0);
+        let green = V3d::new(0.0, 1.0, 0.0);
+
+        let blended = blend_material(
+            Material::from_color((255, 0, 0)),
+            Material::from_color((0, 255, 0)),
+            1.0,
+        );
+        assert!((blended.diffuse - red).norm2() < 1e-6);
+
+        let blended = blend_material(
+            Material::from_color((255, 0, 0)),
+            Material::from_color((0, 255, 0)),
+            0.0,
+        );
+        assert!((blended.diffuse - green).norm2() < 1e-6);
+    }
+}
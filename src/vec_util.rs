@@ -8,3 +8,46 @@ pub fn normalize(v: V3d) -> V3d {
 pub fn length(v: V3d) -> f64 {
     f64::sqrt(v.norm2())
 }
+
+pub fn mul_elementwise(a: V3d, b: V3d) -> V3d {
+    V3d::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+
+pub fn cross(a: V3d, b: V3d) -> V3d {
+    V3d::new(
+        a.y * b.z - a.z * b.y,
+        a.z * b.x - a.x * b.z,
+        a.x * b.y - a.y * b.x,
+    )
+}
+
+pub fn random_in_unit_disk() -> V3d {
+    loop {
+        let p = V3d::new(
+            2.0 * rand::random::<f64>() - 1.0,
+            2.0 * rand::random::<f64>() - 1.0,
+            0.0,
+        );
+        if p.norm2() < 1.0 {
+            return p;
+        }
+    }
+}
+
+pub fn cosine_sample_hemisphere(normal: V3d) -> V3d {
+    let r1 = rand::random::<f64>();
+    let r2 = rand::random::<f64>();
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let sin_theta = f64::sqrt(r2);
+    let cos_theta = f64::sqrt(1.0 - r2);
+
+    let helper = if normal.x.abs() > 0.9 {
+        V3d::new(0., 1., 0.)
+    } else {
+        V3d::new(1., 0., 0.)
+    };
+    let tangent = normalize(cross(helper, normal));
+    let bitangent = cross(normal, tangent);
+
+    tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + normal * cos_theta
+}